@@ -0,0 +1,140 @@
+/*
+ * Copyright (C) 2023 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! AES cipher context backing the symmetric DMA operations.
+//!
+//! The context owns a handle to the AES crypto engine and drives it over the shared
+//! [`DmaRegionSet`](crate::dma::DmaRegionSet) regions. Keys and IVs are extracted from the
+//! [`HwCryptoKeyMaterial`]/[`SymmetricOperationParameters`] bound at `begin` time; the engine
+//! consumes input regions and emits into output regions asynchronously.
+
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::HwCryptoKeyMaterial::HwCryptoKeyMaterial;
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::SymmetricOperationParameters::SymmetricOperationParameters;
+
+use crate::dma::DmaRegionSet;
+use crate::err::HwCryptoError;
+use crate::key::aes_key_bytes;
+
+/// Opaque handle to the AES crypto engine (Trusty `lib/hwaes`).
+#[repr(C)]
+struct EngineHandle(*mut core::ffi::c_void);
+
+extern "C" {
+    fn hwaes_open(key: *const u8, key_len: usize, iv: *const u8, iv_len: usize, encrypt: bool) -> EngineHandle;
+    fn hwaes_enqueue(
+        engine: EngineHandle,
+        src: *const u8,
+        dst: *mut u8,
+        len: usize,
+    ) -> i32;
+    fn hwaes_busy(engine: EngineHandle) -> bool;
+    fn hwaes_drain(engine: EngineHandle) -> i32;
+    fn hwaes_finish(engine: EngineHandle) -> i32;
+    fn hwaes_close(engine: EngineHandle);
+}
+
+const HWAES_OK: i32 = 0;
+
+pub(crate) struct AesCipherContext {
+    engine: EngineHandle,
+    queued: bool,
+}
+
+// SAFETY: the engine handle is only accessed while the operation mutex is held.
+unsafe impl Send for AesCipherContext {}
+
+impl AesCipherContext {
+    pub(crate) fn new(
+        key: &HwCryptoKeyMaterial,
+        parameters: &SymmetricOperationParameters,
+    ) -> Result<Self, HwCryptoError> {
+        let key_bytes = aes_key_bytes(key)?;
+        let iv = parameters.nonce.as_slice();
+        // SAFETY: pointers/lengths are valid for the duration of the call.
+        let engine = unsafe {
+            hwaes_open(
+                key_bytes.as_ptr(),
+                key_bytes.len(),
+                iv.as_ptr(),
+                iv.len(),
+                parameters.direction == crate::key::ENCRYPT,
+            )
+        };
+        if engine.0.is_null() {
+            return Err(HwCryptoError::InvalidKeyMaterial);
+        }
+        Ok(AesCipherContext { engine, queued: false })
+    }
+
+    /// Enqueue the bound input regions for the engine to consume into the output regions. Regions
+    /// are drained pairwise in order; each input region must have the same length as the output
+    /// region it is paired with.
+    pub(crate) fn process(
+        &mut self,
+        input: &DmaRegionSet,
+        output: &DmaRegionSet,
+    ) -> Result<(), HwCryptoError> {
+        if input.total_len() != output.total_len() {
+            return Err(HwCryptoError::BadParameters);
+        }
+        for (src, dst) in input.regions().iter().zip(output.regions()) {
+            if src.len() != dst.len() {
+                return Err(HwCryptoError::BadParameters);
+            }
+            // SAFETY: both spans are live mappings for the operation's lifetime.
+            let rc = unsafe { hwaes_enqueue(self.engine, src.base(), dst.base(), src.len()) };
+            if rc != HWAES_OK {
+                return Err(HwCryptoError::Generic);
+            }
+        }
+        self.queued = true;
+        Ok(())
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        // SAFETY: engine handle is valid until `finish`/`abort`.
+        self.queued && unsafe { hwaes_busy(self.engine) }
+    }
+
+    pub(crate) fn drain(&mut self) -> Result<(), HwCryptoError> {
+        // SAFETY: engine handle is valid until `finish`/`abort`.
+        if unsafe { hwaes_drain(self.engine) } != HWAES_OK {
+            return Err(HwCryptoError::Generic);
+        }
+        self.queued = false;
+        Ok(())
+    }
+
+    pub(crate) fn finish(&mut self) -> Result<(), HwCryptoError> {
+        // SAFETY: engine handle is valid until `finish`/`abort`.
+        if unsafe { hwaes_finish(self.engine) } != HWAES_OK {
+            return Err(HwCryptoError::Generic);
+        }
+        self.queued = false;
+        Ok(())
+    }
+
+    pub(crate) fn abort(&mut self) {
+        self.queued = false;
+    }
+}
+
+impl Drop for AesCipherContext {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned and dropped exactly once.
+        unsafe { hwaes_close(self.engine) };
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright (C) 2023 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Shared scatter/gather memory used by the DMA crypto paths.
+//!
+//! The crypto engine reads and writes these regions directly, so the HAL never copies payload bytes
+//! through user space. It only maps the shared handles, records the spans, and performs the cache
+//! maintenance that keeps the CPU and the engine coherent across the shared mapping.
+
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::MemoryBufferReference::MemoryBufferReference;
+
+use crate::err::HwCryptoError;
+
+extern "C" {
+    // Trusty `lib/dma` cache-maintenance primitives.
+    fn prepare_dma(addr: *mut core::ffi::c_void, size: usize, flags: u32) -> i32;
+    fn finish_dma(addr: *mut core::ffi::c_void, size: usize, flags: u32);
+}
+
+const DMA_FLAG_TO_DEVICE: u32 = 0x1;
+const DMA_FLAG_FROM_DEVICE: u32 = 0x2;
+
+/// A single contiguous shared-memory region bound to a DMA operation.
+pub(crate) struct DmaRegion {
+    /// Mapped base address of the region in this process' address space.
+    base: *mut u8,
+    /// Length of the region in bytes.
+    len: usize,
+}
+
+// SAFETY: the region is only ever touched while the owning `Operation` mutex is held, and cache
+// maintenance is issued explicitly around engine hand-off, so the raw pointer is sound to move
+// between threads alongside the operation.
+unsafe impl Send for DmaRegion {}
+
+impl DmaRegion {
+    fn map(reference: &MemoryBufferReference) -> Result<Self, HwCryptoError> {
+        if reference.size_bytes <= 0 || reference.start_offset < 0 {
+            return Err(HwCryptoError::BadParameters);
+        }
+        let base = reference
+            .memory
+            .map(reference.start_offset as usize, reference.size_bytes as usize)
+            .map_err(|_| HwCryptoError::BadParameters)?;
+        Ok(DmaRegion { base, len: reference.size_bytes as usize })
+    }
+
+    fn end(&self) -> usize {
+        self.base as usize + self.len
+    }
+
+    /// Returns true if this region's mapping aliases `other`'s.
+    ///
+    /// NOTE: this compares process-local virtual addresses of the mapped handles, so it only catches
+    /// the case where both sides map the *same* shared handle at the same span. Two distinct handles
+    /// that physically alias are mapped at unrelated VAs and will not be flagged here — the shared
+    /// `MemoryBufferReference` handles carry no process-stable identity we can compare for physical
+    /// aliasing, so that case is out of scope for this guard.
+    fn overlaps(&self, other: &DmaRegion) -> bool {
+        let a = self.base as usize;
+        let b = other.base as usize;
+        a < other.end() && b < self.end()
+    }
+
+    pub(crate) fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// An ordered set of DMA regions forming one side (input or output) of an operation.
+pub(crate) struct DmaRegionSet {
+    regions: Vec<DmaRegion>,
+}
+
+impl DmaRegionSet {
+    pub(crate) fn from_buffers(buffers: &[MemoryBufferReference]) -> Result<Self, HwCryptoError> {
+        let regions = buffers.iter().map(DmaRegion::map).collect::<Result<Vec<_>, _>>()?;
+        Ok(DmaRegionSet { regions })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.total_len() == 0
+    }
+
+    pub(crate) fn total_len(&self) -> usize {
+        self.regions.iter().map(|r| r.len).sum()
+    }
+
+    /// Returns true if any region on this side aliases any region on `other`.
+    pub(crate) fn overlaps(&self, other: &DmaRegionSet) -> bool {
+        self.regions.iter().any(|a| other.regions.iter().any(|b| a.overlaps(b)))
+    }
+
+    /// Flush dirty CPU lines out to memory so the engine observes the bytes we staged. Issued over
+    /// the input regions before the engine is kicked.
+    pub(crate) fn clean_cache(&self) {
+        for region in &self.regions {
+            // SAFETY: `base`/`len` describe a live mapping for the lifetime of the operation.
+            unsafe {
+                prepare_dma(region.base as *mut core::ffi::c_void, region.len, DMA_FLAG_TO_DEVICE);
+            }
+        }
+    }
+
+    /// Drop stale CPU lines so reads observe what the engine wrote. Issued over the output regions
+    /// once the engine has drained.
+    pub(crate) fn invalidate_cache(&self) {
+        for region in &self.regions {
+            // SAFETY: `base`/`len` describe a live mapping for the lifetime of the operation.
+            unsafe {
+                finish_dma(region.base as *mut core::ffi::c_void, region.len, DMA_FLAG_FROM_DEVICE);
+            }
+        }
+    }
+
+    pub(crate) fn regions(&self) -> &[DmaRegion] {
+        &self.regions
+    }
+}
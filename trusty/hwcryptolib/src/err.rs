@@ -0,0 +1,75 @@
+/*
+ * Copyright (C) 2023 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Internal error type for the symmetric operations.
+//!
+//! The AIDL interface surfaces non-fatal failures as a [`HalErrorCode`] inside the relevant
+//! `*OperationResult`; callers never see a panic. Implementations work with this richer enum and
+//! convert to the wire code at the result boundary via `From`.
+
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::HalErrorCode::HalErrorCode;
+
+/// Failure modes common to the symmetric operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HwCryptoError {
+    /// The requested cipher/mode combination is not implemented.
+    UnsupportedAlgorithm,
+    /// The supplied key material is opaque, the wrong length, or otherwise unusable.
+    InvalidKeyMaterial,
+    /// The operation parameters (nonce, tag length, buffer layout, ...) are invalid.
+    BadParameters,
+    /// The hardware engine is still draining a previous request.
+    Busy,
+    /// The operation was aborted and can no longer be used.
+    Aborted,
+    /// Authentication failed: the AEAD tag did not verify.
+    VerificationFailed,
+    /// A lower-level engine or transport failure with no more specific cause.
+    Generic,
+}
+
+impl From<HwCryptoError> for HalErrorCode {
+    fn from(err: HwCryptoError) -> HalErrorCode {
+        match err {
+            HwCryptoError::UnsupportedAlgorithm => HalErrorCode::UNSUPPORTED,
+            HwCryptoError::InvalidKeyMaterial => HalErrorCode::BAD_KEY,
+            HwCryptoError::BadParameters => HalErrorCode::BAD_PARAMETER,
+            HwCryptoError::Busy => HalErrorCode::BUSY,
+            HwCryptoError::Aborted => HalErrorCode::ABORTED,
+            HwCryptoError::VerificationFailed => HalErrorCode::VERIFICATION_FAILED,
+            HwCryptoError::Generic => HalErrorCode::GENERIC_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_each_variant_to_its_wire_code() {
+        assert_eq!(HalErrorCode::from(HwCryptoError::UnsupportedAlgorithm), HalErrorCode::UNSUPPORTED);
+        assert_eq!(HalErrorCode::from(HwCryptoError::InvalidKeyMaterial), HalErrorCode::BAD_KEY);
+        assert_eq!(HalErrorCode::from(HwCryptoError::BadParameters), HalErrorCode::BAD_PARAMETER);
+        assert_eq!(HalErrorCode::from(HwCryptoError::Busy), HalErrorCode::BUSY);
+        assert_eq!(HalErrorCode::from(HwCryptoError::Aborted), HalErrorCode::ABORTED);
+        assert_eq!(
+            HalErrorCode::from(HwCryptoError::VerificationFailed),
+            HalErrorCode::VERIFICATION_FAILED
+        );
+        assert_eq!(HalErrorCode::from(HwCryptoError::Generic), HalErrorCode::GENERIC_ERROR);
+    }
+}
@@ -0,0 +1,48 @@
+/*
+ * Copyright (C) 2023 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Helpers for extracting raw key material and operation direction from the AIDL types.
+
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::HwCryptoKeyMaterial::HwCryptoKeyMaterial;
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::types::SymmetricOperation::SymmetricOperation;
+
+use crate::err::HwCryptoError;
+
+/// `SymmetricOperation::ENCRYPT` re-exported for terse call sites.
+pub(crate) const ENCRYPT: SymmetricOperation = SymmetricOperation::ENCRYPT;
+
+/// Borrow the raw AES key bytes from clear key material.
+///
+/// Only explicit (clear) symmetric key material is usable here; opaque/handle-backed material is
+/// rejected with [`HwCryptoError::InvalidKeyMaterial`].
+pub(crate) fn aes_key_bytes(key: &HwCryptoKeyMaterial) -> Result<&[u8], HwCryptoError> {
+    match key {
+        HwCryptoKeyMaterial::ExplicitKey(material) => {
+            let key_length = match material.key_length {
+                16 | 24 | 32 => material.key_length as usize,
+                _ => return Err(HwCryptoError::InvalidKeyMaterial),
+            };
+            // The declared `key_length` must match the buffer actually supplied; otherwise a blob
+            // with e.g. `key_length: 32` and short/empty `key_material` would reach the cipher with
+            // a mismatched length.
+            if material.key_material.len() != key_length {
+                return Err(HwCryptoError::InvalidKeyMaterial);
+            }
+            Ok(material.key_material.as_slice())
+        }
+        _ => Err(HwCryptoError::InvalidKeyMaterial),
+    }
+}
@@ -0,0 +1,286 @@
+/*
+ * Copyright (C) 2023 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Implementation of the non-DMA AEAD symmetric path (AES-GCM).
+//!
+//! The authentication tag is only trustworthy once every byte of ciphertext and associated data has
+//! been consumed, so on decryption this operation buffers the ciphertext and releases plaintext only
+//! from `finish`, after the tag verifies. A failed verification returns
+//! [`HwCryptoError::VerificationFailed`](crate::err::HwCryptoError) and yields no plaintext.
+
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::HwCryptoKeyMaterial::HwCryptoKeyMaterial;
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::IAeadEmittingOperation::{
+    BnAeadEmittingOperation, IAeadEmittingOperation,
+};
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::AeadOperationResult::AeadOperationResult;
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::EmittingOperationResult::EmittingOperationResult;
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::HalErrorCode::HalErrorCode;
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::SymmetricOperationParameters::SymmetricOperationParameters;
+
+use android_hardware_security_see::binder;
+
+use std::sync::Mutex;
+
+use crate::err::HwCryptoError;
+use crate::key::{aes_key_bytes, ENCRYPT};
+
+/// AES-GCM uses a 96-bit nonce.
+const GCM_NONCE_LEN: usize = 12;
+/// AES-GCM produces a 128-bit authentication tag. The backend emits and consumes the full tag, so
+/// truncated tags are not supported and `tag_len` is constrained to exactly this.
+const GCM_TAG_LEN: usize = 16;
+
+/// Whether this operation encrypts (producing a tag) or decrypts (verifying one).
+enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+struct Operation {
+    key: Vec<u8>,
+    nonce: Vec<u8>,
+    direction: Direction,
+    /// Accumulated associated data.
+    aad: Vec<u8>,
+    /// Accumulated plaintext (encrypt) or ciphertext (decrypt). On decrypt the buffer is only
+    /// turned into plaintext once the tag verifies in `finish`.
+    data: Vec<u8>,
+    finished: bool,
+}
+
+/// The AES-GCM AEAD emitting operation.
+pub struct SymmetricAeadOperation {
+    op: Mutex<Operation>,
+}
+
+impl SymmetricAeadOperation {
+    pub(crate) fn new_operation(
+        key: &HwCryptoKeyMaterial,
+        parameters: &SymmetricOperationParameters,
+    ) -> binder::Result<AeadOperationResult> {
+        match Self::try_new(key, parameters) {
+            Ok(op) => {
+                let binder =
+                    BnAeadEmittingOperation::new_binder(op, binder::BinderFeatures::default());
+                Ok(AeadOperationResult {
+                    error_code: HalErrorCode::NO_ERROR,
+                    operation: Some(binder),
+                })
+            }
+            Err(e) => Ok(AeadOperationResult { error_code: e.into(), operation: None }),
+        }
+    }
+
+    fn try_new(
+        key: &HwCryptoKeyMaterial,
+        parameters: &SymmetricOperationParameters,
+    ) -> Result<Self, HwCryptoError> {
+        let key = aes_key_bytes(key)?.to_vec();
+        // Validate the key size against the available AEAD backends up front, with the same
+        // `GcmKey::from_bytes` check that seal/open use, so an unusable key fails at `begin`.
+        GcmKey::from_bytes(&key)?;
+
+        if parameters.nonce.len() != GCM_NONCE_LEN {
+            return Err(HwCryptoError::BadParameters);
+        }
+        let tag_len = usize::try_from(parameters.tag_len).map_err(|_| HwCryptoError::BadParameters)?;
+        if tag_len != GCM_TAG_LEN {
+            return Err(HwCryptoError::BadParameters);
+        }
+
+        let direction =
+            if parameters.direction == ENCRYPT { Direction::Encrypt } else { Direction::Decrypt };
+
+        Ok(SymmetricAeadOperation {
+            op: Mutex::new(Operation {
+                key,
+                nonce: parameters.nonce.clone(),
+                direction,
+                aad: Vec::new(),
+                data: Vec::new(),
+                finished: false,
+            }),
+        })
+    }
+}
+
+impl binder::Interface for SymmetricAeadOperation {}
+
+impl IAeadEmittingOperation for SymmetricAeadOperation {
+    fn set_associated_data(&self, aad: &[u8]) -> binder::Result<HalErrorCode> {
+        let mut op = self.op.lock().unwrap();
+        if op.finished || !op.data.is_empty() {
+            // AAD must precede any payload, per the AEAD contract.
+            return Ok(HwCryptoError::BadParameters.into());
+        }
+        op.aad.extend_from_slice(aad);
+        Ok(HalErrorCode::NO_ERROR)
+    }
+
+    fn update(&self, input: &[u8]) -> binder::Result<EmittingOperationResult> {
+        let mut op = self.op.lock().unwrap();
+        if op.finished {
+            return Ok(EmittingOperationResult {
+                error_code: HwCryptoError::Aborted.into(),
+                data: Vec::new(),
+            });
+        }
+        // No plaintext is released until the tag is verified, so `update` never emits on the decrypt
+        // path; it only buffers. The encrypt path likewise emits its ciphertext from `finish` so the
+        // tag travels with the last block.
+        op.data.extend_from_slice(input);
+        Ok(EmittingOperationResult { error_code: HalErrorCode::NO_ERROR, data: Vec::new() })
+    }
+
+    fn finish(&self) -> binder::Result<EmittingOperationResult> {
+        let mut op = self.op.lock().unwrap();
+        if op.finished {
+            return Ok(EmittingOperationResult {
+                error_code: HwCryptoError::Aborted.into(),
+                data: Vec::new(),
+            });
+        }
+        op.finished = true;
+
+        let result = match op.direction {
+            Direction::Encrypt => seal(&op.key, &op.nonce, &op.aad, &op.data),
+            Direction::Decrypt => open(&op.key, &op.nonce, &op.aad, &op.data),
+        };
+        match result {
+            Ok(data) => Ok(EmittingOperationResult { error_code: HalErrorCode::NO_ERROR, data }),
+            Err(e) => {
+                // Drop any buffered payload so verification failure releases nothing.
+                op.data.clear();
+                Ok(EmittingOperationResult { error_code: e.into(), data: Vec::new() })
+            }
+        }
+    }
+
+    fn abort(&self) -> binder::Result<()> {
+        let mut op = self.op.lock().unwrap();
+        op.finished = true;
+        op.data.clear();
+        op.aad.clear();
+        Ok(())
+    }
+}
+
+/// Encrypt-and-seal, returning ciphertext with the 128-bit tag appended.
+fn seal(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, HwCryptoError> {
+    Ok(GcmKey::from_bytes(key)?.seal(nonce, aad, plaintext))
+}
+
+/// Verify-and-open ciphertext-with-tag, returning plaintext only if the tag verifies.
+fn open(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, HwCryptoError> {
+    if ciphertext.len() < GCM_TAG_LEN {
+        return Err(HwCryptoError::BadParameters);
+    }
+    GcmKey::from_bytes(key)?
+        .open(nonce, aad, ciphertext)
+        .map_err(|_| HwCryptoError::VerificationFailed)
+}
+
+/// An AES-GCM key bound to the concrete backend for its size.
+///
+/// AES-128-GCM and AES-256-GCM are backed directly; AES-192-GCM has no backend and is rejected so
+/// the accepted key sizes here stay consistent with [`aes_key_bytes`](crate::key::aes_key_bytes).
+enum GcmKey {
+    Aes128(bssl_crypto::aead::Aes128Gcm),
+    Aes256(bssl_crypto::aead::Aes256Gcm),
+}
+
+impl GcmKey {
+    fn from_bytes(key: &[u8]) -> Result<GcmKey, HwCryptoError> {
+        use bssl_crypto::aead::{Aes128Gcm, Aes256Gcm};
+        match key.len() {
+            16 => {
+                let k: [u8; 16] = key.try_into().expect("length checked above");
+                Ok(GcmKey::Aes128(Aes128Gcm::new(&k)))
+            }
+            32 => {
+                let k: [u8; 32] = key.try_into().expect("length checked above");
+                Ok(GcmKey::Aes256(Aes256Gcm::new(&k)))
+            }
+            // 24-byte (AES-192) keys pass `aes_key_bytes` but have no GCM backend here.
+            24 => Err(HwCryptoError::UnsupportedAlgorithm),
+            _ => Err(HwCryptoError::InvalidKeyMaterial),
+        }
+    }
+
+    fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        use bssl_crypto::aead::Aead;
+        match self {
+            GcmKey::Aes128(aead) => aead.seal(nonce, aad, plaintext),
+            GcmKey::Aes256(aead) => aead.seal(nonce, aad, plaintext),
+        }
+    }
+
+    fn open(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, ()> {
+        use bssl_crypto::aead::Aead;
+        match self {
+            GcmKey::Aes128(aead) => aead.open(nonce, aad, ciphertext).map_err(|_| ()),
+            GcmKey::Aes256(aead) => aead.open(nonce, aad, ciphertext).map_err(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONCE: [u8; GCM_NONCE_LEN] = [0x24; GCM_NONCE_LEN];
+
+    #[test]
+    fn seal_then_open_round_trips_for_both_key_sizes() {
+        for key in [vec![0x11; 16], vec![0x11; 32]] {
+            let sealed = seal(&key, &NONCE, b"aad", b"plaintext").expect("seal");
+            // The tag is appended, so the sealed output is longer than the plaintext.
+            assert_eq!(sealed.len(), b"plaintext".len() + GCM_TAG_LEN);
+            let opened = open(&key, &NONCE, b"aad", &sealed).expect("open");
+            assert_eq!(opened, b"plaintext");
+        }
+    }
+
+    #[test]
+    fn open_rejects_tampered_tag() {
+        let key = vec![0x11; 32];
+        let mut sealed = seal(&key, &NONCE, b"aad", b"plaintext").expect("seal");
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0xff;
+        assert_eq!(open(&key, &NONCE, b"aad", &sealed), Err(HwCryptoError::VerificationFailed));
+    }
+
+    #[test]
+    fn open_rejects_mismatched_aad() {
+        let key = vec![0x11; 32];
+        let sealed = seal(&key, &NONCE, b"aad", b"plaintext").expect("seal");
+        assert_eq!(
+            open(&key, &NONCE, b"other", &sealed),
+            Err(HwCryptoError::VerificationFailed)
+        );
+    }
+
+    #[test]
+    fn open_rejects_truncated_input() {
+        let key = vec![0x11; 32];
+        assert_eq!(open(&key, &NONCE, b"aad", &[0; 4]), Err(HwCryptoError::BadParameters));
+    }
+
+    #[test]
+    fn aes192_has_no_backend() {
+        assert_eq!(GcmKey::from_bytes(&[0; 24]).err(), Some(HwCryptoError::UnsupportedAlgorithm));
+    }
+}
@@ -15,9 +15,16 @@
  */
 
 //! Implementation of the `IDmaEmittingOperation` AIDL interface for symmetric cryptography.
+//!
+//! Unlike the copying operations, the buffers handed to us in [`DmaOperationBuffers`] are shared
+//! scatter/gather regions that the crypto engine reads and writes in place. We therefore never copy
+//! plaintext/ciphertext through the HAL: we only drive the engine, perform the cache maintenance the
+//! shared memory requires, and report status back to the caller.
 
 use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::HwCryptoKeyMaterial::HwCryptoKeyMaterial;
-use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::IDmaEmittingOperation::IDmaEmittingOperation;
+use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::IDmaEmittingOperation::{
+    BnDmaEmittingOperation, IDmaEmittingOperation,
+};
 use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::BooleanResult::BooleanResult;
 use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::DmaEmittingOperationResult::DmaEmittingOperationResult;
 use android_hardware_security_see::aidl::android::hardware::security::see::hwcrypto::base_types::DmaOperationBuffers::DmaOperationBuffers;
@@ -26,16 +33,78 @@ use android_hardware_security_see::aidl::android::hardware::security::see::hwcry
 
 use android_hardware_security_see::binder;
 
+use std::sync::Mutex;
+
+use crate::aes_cipher::AesCipherContext;
+use crate::dma::DmaRegionSet;
+use crate::err::HwCryptoError;
+
+/// Lifecycle state of a DMA operation. The engine is single-shot per `finish`/`abort`, mirroring the
+/// copying operations which are consumed on completion.
+enum State {
+    /// Buffers are bound and the engine is idle, ready for `update`.
+    Ready,
+    /// The engine has been kicked and may still be draining queued buffers.
+    Running,
+    /// `finish` has flushed the final block, or `abort` has been called; any further call is
+    /// rejected. `abort` does not stop buffers the engine has already queued; the engine handle is
+    /// closed when the operation is dropped.
+    Done,
+}
+
+struct Operation {
+    cipher: AesCipherContext,
+    input: DmaRegionSet,
+    output: DmaRegionSet,
+    state: State,
+}
+
 /// The `IDmaEmittingOperation` implementation for symmetric cryptography (AES for now).
-pub struct SymmetricDmaEmittingOperation;
+pub struct SymmetricDmaEmittingOperation {
+    op: Mutex<Operation>,
+}
 
 impl SymmetricDmaEmittingOperation {
     pub(crate) fn new_operation(
-        _key: &HwCryptoKeyMaterial,
-        _parameters: &SymmetricOperationParameters,
-        _dma_buffers: &DmaOperationBuffers,
+        key: &HwCryptoKeyMaterial,
+        parameters: &SymmetricOperationParameters,
+        dma_buffers: &DmaOperationBuffers,
     ) -> binder::Result<DmaEmittingOperationResult> {
-        unimplemented!("SymmetricDmaEmittingOperation::new not implemented")
+        match Self::try_new(key, parameters, dma_buffers) {
+            Ok(op) => {
+                let binder =
+                    BnDmaEmittingOperation::new_binder(op, binder::BinderFeatures::default());
+                Ok(DmaEmittingOperationResult {
+                    error_code: HalErrorCode::NO_ERROR,
+                    operation: Some(binder),
+                })
+            }
+            Err(e) => Ok(DmaEmittingOperationResult { error_code: e.into(), operation: None }),
+        }
+    }
+
+    fn try_new(
+        key: &HwCryptoKeyMaterial,
+        parameters: &SymmetricOperationParameters,
+        dma_buffers: &DmaOperationBuffers,
+    ) -> Result<Self, HwCryptoError> {
+        let input = DmaRegionSet::from_buffers(&dma_buffers.input)?;
+        let output = DmaRegionSet::from_buffers(&dma_buffers.output)?;
+
+        // Shared DMA memory must not alias: the engine would otherwise read partially-written
+        // ciphertext back as plaintext. Zero-length buffers carry no work and are almost always a
+        // caller bug, so reject them up front rather than silently succeeding.
+        if input.is_empty() || output.is_empty() {
+            return Err(HwCryptoError::BadParameters);
+        }
+        if input.overlaps(&output) {
+            return Err(HwCryptoError::BadParameters);
+        }
+
+        let cipher = AesCipherContext::new(key, parameters)?;
+        Ok(SymmetricDmaEmittingOperation {
+            op: Mutex::new(Operation { cipher, input, output, state: State::Ready }),
+        })
     }
 }
 
@@ -43,22 +112,65 @@ impl binder::Interface for SymmetricDmaEmittingOperation {}
 
 impl IDmaEmittingOperation for SymmetricDmaEmittingOperation {
     fn update(&self) -> binder::Result<HalErrorCode> {
-        unimplemented!("update not implemented")
+        let mut op = self.op.lock().unwrap();
+        if matches!(op.state, State::Done) {
+            return Ok(HalErrorCode::GENERIC_ERROR);
+        }
+        // Make the queued plaintext visible to the engine before it starts; the engine writes the
+        // output regions, which we invalidate once it has drained (see `wait_for_completion`).
+        op.input.clean_cache();
+        let Operation { cipher, input, output, state } = &mut *op;
+        match cipher.process(input, output) {
+            Ok(()) => {
+                *state = State::Running;
+                Ok(HalErrorCode::NO_ERROR)
+            }
+            Err(e) => Ok(e.into()),
+        }
     }
 
     fn is_busy(&self) -> binder::Result<BooleanResult> {
-        unimplemented!("is_busy not implemented")
+        let op = self.op.lock().unwrap();
+        let value = matches!(op.state, State::Running) && op.cipher.is_draining();
+        Ok(BooleanResult { value })
     }
 
     fn wait_for_completion(&self) -> binder::Result<HalErrorCode> {
-        unimplemented!("wait_for_completion not implemented")
+        let mut op = self.op.lock().unwrap();
+        if matches!(op.state, State::Done) {
+            return Ok(HalErrorCode::GENERIC_ERROR);
+        }
+        match op.cipher.drain() {
+            Ok(()) => {
+                // Results have landed in shared memory behind the CPU cache; invalidate so reads on
+                // this side observe the engine's writes.
+                op.output.invalidate_cache();
+                Ok(HalErrorCode::NO_ERROR)
+            }
+            Err(e) => Ok(e.into()),
+        }
     }
 
     fn finish(&self) -> binder::Result<HalErrorCode> {
-        unimplemented!("finish not implemented")
+        let mut op = self.op.lock().unwrap();
+        if matches!(op.state, State::Done) {
+            return Ok(HalErrorCode::GENERIC_ERROR);
+        }
+        let result = match op.cipher.finish() {
+            Ok(()) => {
+                op.output.invalidate_cache();
+                HalErrorCode::NO_ERROR
+            }
+            Err(e) => e.into(),
+        };
+        op.state = State::Done;
+        Ok(result)
     }
 
     fn abort(&self) -> binder::Result<()> {
-        unimplemented!("abort not implemented")
+        let mut op = self.op.lock().unwrap();
+        op.cipher.abort();
+        op.state = State::Done;
+        Ok(())
     }
 }
@@ -0,0 +1,362 @@
+//
+// Copyright (C) 2022 The Android Open-Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Locally generated DICE/BCC attestation chain.
+//!
+//! For test and bring-up configurations the Trusty TA may have no provisioned boot certificate
+//! chain. In that case the HAL can synthesise its own open-dice-style chain here rather than blindly
+//! forwarding `IRemotelyProvisionedComponent` calls to a TA that cannot answer them. The chain is a
+//! CBOR array whose first element is the root `COSE_Key` and whose remaining elements are
+//! `CoseSign1`-wrapped CWTs, each signed by the previous stage's private key. The resulting BCC is
+//! embedded into CSR assembly by `generateCertificateRequestV2`.
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::MacedPublicKey::MacedPublicKey;
+use ciborium::value::Value;
+use coset::iana;
+use coset::{
+    CborSerializable, CoseKey, CoseKeyBuilder, CoseMac0, CoseSign1, CoseSign1Builder, HeaderBuilder,
+};
+use diced_open_dice::{
+    derive_cdi_private_key_seed, hash, keypair_from_seed, sign, DiceError, PrivateKey, PublicKey,
+};
+
+/// A locally generated boot certificate chain (BCC) together with the leaf key pair that CSR
+/// assembly signs the protected request with.
+pub struct LocalDiceChain {
+    /// The serialized BCC: a CBOR array `[root_COSE_Key, CoseSign1, ...]`.
+    pub bcc: Vec<u8>,
+    /// Sealing CDI derived alongside the attestation CDI; retained for `ISharedSecret`-style use.
+    pub cdi_seal: [u8; 32],
+    /// Leaf private key; the component signs its protected CSR payload with this.
+    pub leaf_private_key: PrivateKey,
+    /// Leaf public key, matching the `subjectPublicKey` of the final certificate.
+    pub leaf_public_key: PublicKey,
+}
+
+/// CWT claim labels, per the RKP `ProtectedData`/BCC specification.
+const ISS: i64 = 1;
+const SUB: i64 = 2;
+const SUBJECT_PUBLIC_KEY: i64 = -4670552;
+const KEY_USAGE: i64 = -4670553;
+const CODE_HASH: i64 = -4670545;
+const CONFIG_DESC: i64 = -4670548;
+
+/// Component-name key inside the configuration descriptor map (open-dice `CONFIG_DESC` contents).
+const CONFIG_COMPONENT_NAME: i64 = -70002;
+
+// `keyCertSign` key usage, as a one-byte bit string.
+const KEY_USAGE_CERT_SIGN: [u8; 1] = [0x20];
+
+// RKP v3 CSR schema versions: the outer `AuthenticatedRequest` and the inner `CsrPayload`.
+const AUTH_REQ_VERSION: i64 = 1;
+const CSR_PAYLOAD_VERSION: i64 = 3;
+
+/// Build a local DICE chain from a configured `seed` and a `measurement` (hash of the HAL stage).
+///
+/// `CDI_attest` and `CDI_seal` are derived from the seed and measurement; the attestation CDI seeds
+/// the leaf key pair. The root is carried as a bare `COSE_Key`, and a single CWT signed by the root
+/// key certifies the leaf stage.
+pub fn build_local_chain(seed: &[u8], measurement: &[u8]) -> Result<LocalDiceChain, DiceError> {
+    let code_hash = hash(measurement)?;
+
+    // Root identity is derived from the configured seed alone; the measurement is not folded into
+    // the root so the root key is stable across HAL builds for a given seed. Distinct builds diverge
+    // at the leaf, whose CDI below binds the measurement (`code_hash`).
+    let root_seed = derive_cdi_private_key_seed(seed)?;
+    let (root_public, root_private) = keypair_from_seed(root_seed.as_array())?;
+
+    // Leaf identity derived from the attestation CDI.
+    let mut leaf_input = seed.to_vec();
+    leaf_input.extend_from_slice(&code_hash);
+    let cdi_seal = hash(&leaf_input)?[..32].try_into().expect("hash is 64 bytes");
+    let leaf_seed = derive_cdi_private_key_seed(&leaf_input)?;
+    let (leaf_public, leaf_private) = keypair_from_seed(leaf_seed.as_array())?;
+
+    let root_cose_key = ed25519_cose_key(&root_public);
+    let entry = sign_cwt(
+        "root",
+        "leaf",
+        &leaf_public,
+        &code_hash,
+        &root_private,
+    )?;
+
+    // The Android BCC format is `[COSE_Key, + COSE_Sign1]` with the entries emitted as bare
+    // `COSE_Sign1` arrays, not wrapped in a CBOR byte string, so external RKP verifiers accept it.
+    let bcc = Value::Array(vec![cose_key_value(&root_cose_key)?, cose_sign1_value(&entry)?]);
+    let mut bcc_bytes = Vec::new();
+    ciborium::ser::into_writer(&bcc, &mut bcc_bytes).map_err(|_| DiceError::InvalidInput)?;
+
+    let chain = LocalDiceChain {
+        bcc: bcc_bytes,
+        cdi_seal,
+        leaf_private_key: leaf_private,
+        leaf_public_key: leaf_public,
+    };
+    validate_chain(&chain.bcc)?;
+    Ok(chain)
+}
+
+/// Build the Ed25519 `COSE_Key` for a raw public key.
+fn ed25519_cose_key(public_key: &PublicKey) -> CoseKey {
+    CoseKeyBuilder::new_okp_key()
+        .param(iana::OkpKeyParameter::Crv as i64, Value::from(iana::EllipticCurve::Ed25519 as i64))
+        .param(iana::OkpKeyParameter::X as i64, Value::Bytes(public_key.to_vec()))
+        .add_key_op(iana::KeyOperation::Verify)
+        .build()
+}
+
+fn cose_key_value(key: &CoseKey) -> Result<Value, DiceError> {
+    let bytes = key.clone().to_vec().map_err(|_| DiceError::InvalidInput)?;
+    ciborium::de::from_reader(&bytes[..]).map_err(|_| DiceError::InvalidInput)
+}
+
+/// Re-decode a `CoseSign1` into a bare CBOR array `Value` for inline embedding in the BCC array.
+fn cose_sign1_value(entry: &CoseSign1) -> Result<Value, DiceError> {
+    let bytes = entry.clone().to_vec().map_err(|_| DiceError::InvalidInput)?;
+    ciborium::de::from_reader(&bytes[..]).map_err(|_| DiceError::InvalidInput)
+}
+
+/// Emit a `CoseSign1`-wrapped CWT for one stage, signed with the previous stage's private key.
+fn sign_cwt(
+    issuer: &str,
+    subject: &str,
+    subject_public_key: &PublicKey,
+    code_hash: &[u8],
+    signing_key: &PrivateKey,
+) -> Result<CoseSign1, DiceError> {
+    let subject_key = ed25519_cose_key(subject_public_key);
+    let subject_key_bytes = subject_key.to_vec().map_err(|_| DiceError::InvalidInput)?;
+
+    // The configuration descriptor is itself a CBOR map carried as a byte string; it names the
+    // stage being certified so the claim is not vacuous.
+    let config_desc = Value::Map(vec![(
+        Value::from(CONFIG_COMPONENT_NAME),
+        Value::Text(subject.to_string()),
+    )]);
+    let mut config_desc_bytes = Vec::new();
+    ciborium::ser::into_writer(&config_desc, &mut config_desc_bytes)
+        .map_err(|_| DiceError::InvalidInput)?;
+
+    let payload = Value::Map(vec![
+        (Value::from(ISS), Value::Text(issuer.to_string())),
+        (Value::from(SUB), Value::Text(subject.to_string())),
+        (Value::from(SUBJECT_PUBLIC_KEY), Value::Bytes(subject_key_bytes)),
+        (Value::from(KEY_USAGE), Value::Bytes(KEY_USAGE_CERT_SIGN.to_vec())),
+        (Value::from(CODE_HASH), Value::Bytes(code_hash.to_vec())),
+        (Value::from(CONFIG_DESC), Value::Bytes(config_desc_bytes)),
+    ]);
+    let mut payload_bytes = Vec::new();
+    ciborium::ser::into_writer(&payload, &mut payload_bytes).map_err(|_| DiceError::InvalidInput)?;
+
+    let protected = HeaderBuilder::new().algorithm(iana::Algorithm::EdDSA).build();
+    CoseSign1Builder::new()
+        .protected(protected)
+        .payload(payload_bytes)
+        .try_create_signature(&[], |message| sign_bytes(message, signing_key))?
+        .build()
+        .pipe(Ok)
+}
+
+fn sign_bytes(message: &[u8], signing_key: &PrivateKey) -> Result<Vec<u8>, DiceError> {
+    Ok(sign(message, signing_key.as_array())?.to_vec())
+}
+
+/// Assemble a `generateCertificateRequestV2` CSR around the locally-built BCC.
+///
+/// The output is an RKP v3 `AuthenticatedRequest<CsrPayload>`:
+/// `[version, UdsCerts, DiceCertChain, SignedData<[challenge, bstr .cbor CsrPayload]>]`, where
+/// `DiceCertChain` is [`LocalDiceChain::bcc`] and the `SignedData` is a `COSE_Sign1` signed with the
+/// leaf (CDI) private key. This is what lets the locally-generated chain actually reach an RKP
+/// backend instead of being discarded.
+pub fn build_csr(
+    chain: &LocalDiceChain,
+    keys_to_sign: &[MacedPublicKey],
+    challenge: &[u8],
+) -> Result<Vec<u8>, DiceError> {
+    let keys = keys_to_sign
+        .iter()
+        .map(|k| maced_public_to_cose(&k.macedKey))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // CsrPayload = [version, certificate_type, DeviceInfo, KeysToSign].
+    let csr_payload = Value::Array(vec![
+        Value::from(CSR_PAYLOAD_VERSION),
+        Value::Text("keymint".to_string()),
+        device_info(),
+        Value::Array(keys),
+    ]);
+    let csr_payload_bytes = encode(&csr_payload);
+
+    // SignedData payload = [challenge, bstr .cbor CsrPayload], signed by the leaf key.
+    let signed_payload = Value::Array(vec![
+        Value::Bytes(challenge.to_vec()),
+        Value::Bytes(csr_payload_bytes),
+    ]);
+    let protected = HeaderBuilder::new().algorithm(iana::Algorithm::EdDSA).build();
+    let signed_data = CoseSign1Builder::new()
+        .protected(protected)
+        .payload(encode(&signed_payload))
+        .try_create_signature(&[], |message| sign_bytes(message, &chain.leaf_private_key))?
+        .build();
+
+    // DiceCertChain is the BCC embedded inline (as a bare CBOR array, not a byte string).
+    let dice_chain: Value =
+        ciborium::de::from_reader(&chain.bcc[..]).map_err(|_| DiceError::InvalidInput)?;
+
+    let auth_req = Value::Array(vec![
+        Value::from(AUTH_REQ_VERSION),
+        Value::Map(Vec::new()), // UdsCerts: empty for locally-generated chains.
+        dice_chain,
+        cose_sign1_value(&signed_data)?,
+    ]);
+    Ok(encode(&auth_req))
+}
+
+/// Extract the `COSE_Key` carried in the payload of a `MacedPublicKey` (a `COSE_Mac0`).
+fn maced_public_to_cose(maced_key: &[u8]) -> Result<Value, DiceError> {
+    let mac0 = CoseMac0::from_slice(maced_key).map_err(|_| DiceError::InvalidInput)?;
+    let payload = mac0.payload.ok_or(DiceError::InvalidInput)?;
+    ciborium::de::from_reader(&payload[..]).map_err(|_| DiceError::InvalidInput)
+}
+
+/// Minimal `DeviceInfo` map. Bring-up/test configurations carry placeholder provenance rather than
+/// real factory-fused values; a full device integration replaces these.
+fn device_info() -> Value {
+    Value::Map(vec![
+        (Value::Text("brand".to_string()), Value::Text("trusty".to_string())),
+        (Value::Text("manufacturer".to_string()), Value::Text("aosp".to_string())),
+        (Value::Text("product".to_string()), Value::Text("trusty".to_string())),
+        (Value::Text("model".to_string()), Value::Text("trusty".to_string())),
+        (Value::Text("device".to_string()), Value::Text("trusty".to_string())),
+        (Value::Text("vb_state".to_string()), Value::Text("orange".to_string())),
+        (Value::Text("bootloader_state".to_string()), Value::Text("unlocked".to_string())),
+        (Value::Text("security_level".to_string()), Value::Text("tee".to_string())),
+        (Value::Text("fused".to_string()), Value::from(0)),
+    ])
+}
+
+/// Validate a serialized BCC back to its root: the array must start with a `COSE_Key` and every
+/// subsequent `CoseSign1` must verify under the public key carried by the entry before it.
+pub fn validate_chain(bcc: &[u8]) -> Result<(), DiceError> {
+    let value: Value = ciborium::de::from_reader(bcc).map_err(|_| DiceError::InvalidInput)?;
+    let entries = value.as_array().ok_or(DiceError::InvalidInput)?;
+    if entries.len() < 2 {
+        return Err(DiceError::InvalidInput);
+    }
+
+    let root_bytes = entries[0].as_bytes().cloned().unwrap_or_else(|| encode(&entries[0]));
+    let mut signer = ed25519_public_from_cose(&root_bytes)?;
+
+    for entry in &entries[1..] {
+        let sign1_bytes = entry.as_bytes().cloned().unwrap_or_else(|| encode(entry));
+        let sign1 = CoseSign1::from_slice(&sign1_bytes).map_err(|_| DiceError::InvalidInput)?;
+        sign1
+            .verify_signature(&[], |sig, data| verify(data, sig, &signer))
+            .map_err(|_| DiceError::InvalidInput)?;
+
+        let payload = sign1.payload.as_ref().ok_or(DiceError::InvalidInput)?;
+        signer = subject_public_from_cwt(payload)?;
+    }
+    Ok(())
+}
+
+fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let _ = ciborium::ser::into_writer(value, &mut bytes);
+    bytes
+}
+
+fn ed25519_public_from_cose(bytes: &[u8]) -> Result<PublicKey, DiceError> {
+    let key = CoseKey::from_slice(bytes).map_err(|_| DiceError::InvalidInput)?;
+    for (label, value) in &key.params {
+        if let coset::Label::Int(l) = label {
+            if *l == iana::OkpKeyParameter::X as i64 {
+                let raw = value.as_bytes().ok_or(DiceError::InvalidInput)?;
+                return PublicKey::from_slice(raw).map_err(|_| DiceError::InvalidInput);
+            }
+        }
+    }
+    Err(DiceError::InvalidInput)
+}
+
+fn subject_public_from_cwt(payload: &[u8]) -> Result<PublicKey, DiceError> {
+    let value: Value = ciborium::de::from_reader(payload).map_err(|_| DiceError::InvalidInput)?;
+    let map = value.as_map().ok_or(DiceError::InvalidInput)?;
+    for (label, value) in map {
+        if label.as_integer() == Some(SUBJECT_PUBLIC_KEY.into()) {
+            let key_bytes = value.as_bytes().ok_or(DiceError::InvalidInput)?;
+            return ed25519_public_from_cose(key_bytes);
+        }
+    }
+    Err(DiceError::InvalidInput)
+}
+
+fn verify(data: &[u8], sig: &[u8], public_key: &PublicKey) -> Result<(), DiceError> {
+    diced_open_dice::verify(data, sig, public_key.as_array()).map_err(|_| DiceError::InvalidInput)
+}
+
+/// Small `.pipe()` helper to keep the builder chains readable.
+trait Pipe: Sized {
+    fn pipe<R>(self, f: impl FnOnce(Self) -> R) -> R {
+        f(self)
+    }
+}
+impl<T> Pipe for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locally_built_chain_validates_back_to_root() {
+        let chain = build_local_chain(&[0x42; 32], b"hal-stage-image").expect("chain builds");
+        validate_chain(&chain.bcc).expect("freshly built chain verifies");
+    }
+
+    #[test]
+    fn tampered_chain_fails_validation() {
+        let chain = build_local_chain(&[0x42; 32], b"hal-stage-image").expect("chain builds");
+        let mut corrupted = chain.bcc.clone();
+        // Flip a byte in the signed entry; the CoseSign1 signature must no longer verify.
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        assert!(validate_chain(&corrupted).is_err());
+    }
+
+    #[test]
+    fn csr_embeds_the_dice_chain() {
+        let chain = build_local_chain(&[0x42; 32], b"hal-stage-image").expect("chain builds");
+        let expected_chain = chain.bcc.clone();
+        let csr = build_csr(&chain, &[], b"challenge").expect("csr builds");
+
+        let value: Value = ciborium::de::from_reader(&csr[..]).expect("csr is cbor");
+        let entries = value.as_array().expect("AuthenticatedRequest is an array");
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].as_integer(), Some(AUTH_REQ_VERSION.into()));
+
+        // The third element is the embedded DiceCertChain; it must still validate to its root.
+        let embedded = encode(&entries[2]);
+        assert_eq!(embedded, expected_chain);
+        validate_chain(&embedded).expect("embedded chain verifies");
+    }
+
+    #[test]
+    fn distinct_measurements_yield_distinct_leaves() {
+        let a = build_local_chain(&[0x42; 32], b"stage-a").expect("chain builds");
+        let b = build_local_chain(&[0x42; 32], b"stage-b").expect("chain builds");
+        assert_ne!(a.leaf_public_key.to_vec(), b.leaf_public_key.to_vec());
+    }
+}
@@ -14,6 +14,9 @@
 // limitations under the License.
 
 //! This module implements the HAL service for Keymint (Rust) in Trusty.
+mod dice;
+mod local_rpc;
+
 use kmr_hal::{keymint, rpc, secureclock, send_hal_info, sharedsecret, SerializedChannel};
 use log::{debug, error, info};
 use std::{
@@ -21,68 +24,223 @@ use std::{
     ops::DerefMut,
     panic,
     sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 use trusty::DEFAULT_DEVICE;
 
 const TRUSTY_KEYMINT_RUST_SERVICE_NAME: &str = "com.android.trusty.keymint";
 
-// TODO(b/195310053): This HAL service currently runs as a parallel /rust instance of the HAL
-// service(s), to allow development and testing of the Rust reference implementation of KeyMint.
-// Once the Rust version becomes the default, this should become "default".
-static SERVICE_INSTANCE: &str = "rust";
+// The instance suffix all four services are published under. Historically this was hard-coded to
+// "rust" so the Rust reference implementation could run in parallel with the C++ HAL; it can now be
+// promoted to "default" at build time (the `default_instance` feature) or overridden at startup via
+// [`SERVICE_INSTANCE_PROPERTY`]. See b/195310053.
+static SERVICE_INSTANCE_PROPERTY: &str = "ro.keymint.trusty.instance";
+
+/// Select the instance suffix for all four service names.
+///
+/// The compile-time default comes from the `default_instance` feature; a runtime property value of
+/// `"rust"` or `"default"` wins over it so a single build can be repurposed for bring-up. Any other
+/// property value is ignored.
+fn service_instance() -> &'static str {
+    let compiled_default = if cfg!(feature = "default_instance") { "default" } else { "rust" };
+    match rustutils::system_properties::read(SERVICE_INSTANCE_PROPERTY).ok().flatten().as_deref() {
+        Some("default") => "default",
+        Some("rust") => "rust",
+        _ => compiled_default,
+    }
+}
+
+// When set, the HAL builds its own local DICE/BCC attestation chain (see [`dice`]) instead of
+// forwarding `IRemotelyProvisionedComponent` calls to a TA that has no provisioned chain. Intended
+// for test/bring-up configurations only.
+static LOCAL_DICE_PROPERTY: &str = "ro.keymint.trusty.local_dice";
 
 static KM_SERVICE_NAME: &str = "android.hardware.security.keymint.IKeyMintDevice";
 static RPC_SERVICE_NAME: &str = "android.hardware.security.keymint.IRemotelyProvisionedComponent";
 static SECURE_CLOCK_SERVICE_NAME: &str = "android.hardware.security.secureclock.ISecureClock";
 static SHARED_SECRET_SERVICE_NAME: &str = "android.hardware.security.sharedsecret.ISharedSecret";
 
+// First byte of every frame: set while more fragments follow, cleared on the final frame. This is
+// the same marker the receive path (`handle_resp_received`) understands, applied symmetrically to
+// the outgoing request so payloads larger than `MAX_SIZE` are chunked instead of silently dropped.
+const MORE_FRAGMENTS: u8 = 1;
+const LAST_FRAGMENT: u8 = 0;
+
+// Pause before the single reconnect-and-retry, to give a restarting TA time to come back up.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// A resilient connection to a Trusty TA.
+///
+/// The device/port used to establish the connection are retained so that a transport-level failure
+/// (e.g. the TA restarting) can transparently drop the underlying channel, re-`connect`, and retry
+/// the request once instead of permanently wedging the HAL with a `TRANSACTION_FAILED` exception.
 #[derive(Debug)]
-struct TipcChannel(trusty::TipcChannel);
+struct TipcChannel {
+    channel: trusty::TipcChannel,
+    device: &'static str,
+    port: &'static str,
+    /// Set while re-establishing HAL context after a reconnect, to stop `send_hal_info` (which
+    /// transacts on this channel) from triggering another reconnect and recursing.
+    reconnecting: bool,
+}
 
-impl SerializedChannel for TipcChannel {
-    const MAX_SIZE: usize = 4000;
-    fn execute(&mut self, serialized_req: &[u8]) -> binder::Result<Vec<u8>> {
-        self.0.send(serialized_req).map_err(|e| {
-            binder::Status::new_exception(
-                binder::ExceptionCode::TRANSACTION_FAILED,
-                Some(
-                    &CString::new(format!(
-                        "Failed to send the request via tipc channel because of {:?}",
-                        e
-                    ))
-                    .unwrap(),
-                ),
-            )
-        })?;
+fn transport_error(context: &str, e: impl std::fmt::Debug) -> binder::Status {
+    binder::Status::new_exception(
+        binder::ExceptionCode::TRANSACTION_FAILED,
+        Some(&CString::new(format!("{} because of {:?}", context, e)).unwrap()),
+    )
+}
+
+impl TipcChannel {
+    /// Open a resilient channel to `port` on `device`.
+    fn connect(device: &'static str, port: &'static str) -> binder::Result<Self> {
+        let channel = trusty::TipcChannel::connect(device, port)
+            .map_err(|e| transport_error("Failed to connect to the TA", e))?;
+        Ok(TipcChannel { channel, device, port, reconnecting: false })
+    }
+
+    /// Drop the underlying channel, reconnect, and re-send the HAL info so the TA regains context.
+    fn reconnect(&mut self) -> binder::Result<()> {
+        info!("Reconnecting to {}:{} after transport failure.", self.device, self.port);
+        self.channel = trusty::TipcChannel::connect(self.device, self.port)
+            .map_err(|e| transport_error("Failed to reconnect to the TA", e))?;
+        if !self.reconnecting {
+            self.reconnecting = true;
+            let res = send_hal_info(self);
+            self.reconnecting = false;
+            if let Err(e) = res {
+                error!("Failed to repopulate HAL info after reconnect: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Perform one full request/response transaction over the current channel.
+    fn transact(&mut self, serialized_req: &[u8]) -> binder::Result<Vec<u8>> {
+        self.send_fragmented(serialized_req)?;
         let mut expect_more_msgs = true;
         let mut full_rsp = Vec::new();
         while expect_more_msgs {
             let mut recv_buf = Vec::new();
-            self.0.recv(&mut recv_buf).map_err(|e| {
-                binder::Status::new_exception(
-                    binder::ExceptionCode::TRANSACTION_FAILED,
-                    Some(
-                        &CString::new(format!(
-                            "Failed to receive the response via tipc channel because of {:?}",
-                            e
-                        ))
-                        .unwrap(),
-                    ),
-                )
-            })?;
+            self.channel
+                .recv(&mut recv_buf)
+                .map_err(|e| transport_error("Failed to receive the response via tipc channel", e))?;
             let current_rsp_content;
             (expect_more_msgs, current_rsp_content) = Self::handle_resp_received(recv_buf);
             debug!(
-                "In execute: expect more messages: {}, Processed current respone size {}",
+                "In transact: expect more messages: {}, Processed current respone size {}",
                 expect_more_msgs,
                 current_rsp_content.len()
             );
             full_rsp.extend_from_slice(&current_rsp_content);
-            debug!("In execute: Processed full response size yet: {}", full_rsp.len())
+            debug!("In transact: Processed full response size yet: {}", full_rsp.len())
         }
-        debug!("In execute: Full response size: {}", full_rsp.len());
+        debug!("In transact: Full response size: {}", full_rsp.len());
         Ok(full_rsp)
     }
+
+    /// Split `serialized_req` into `MAX_SIZE`-bounded frames and send them in order, each prefixed
+    /// with [`MORE_FRAGMENTS`] except the final frame which carries [`LAST_FRAGMENT`]. An empty
+    /// request is sent as a single final frame so the TA still sees exactly one (complete) message.
+    fn send_fragmented(&mut self, serialized_req: &[u8]) -> binder::Result<()> {
+        // Reserve one byte per frame for the fragment marker.
+        let chunk_size = <Self as SerializedChannel>::MAX_SIZE - 1;
+        for frame in fragment_frames(serialized_req, chunk_size) {
+            self.channel
+                .send(&frame)
+                .map_err(|e| transport_error("Failed to send the request via tipc channel", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Split `serialized_req` into frames of at most `chunk_size` payload bytes, each prefixed with the
+/// fragment marker. Every frame but the last carries [`MORE_FRAGMENTS`]; the last carries
+/// [`LAST_FRAGMENT`] so the TA knows the request is complete. An empty request yields exactly one
+/// (final) frame so the TA still sees a single complete message.
+fn fragment_frames(serialized_req: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut chunks = serialized_req.chunks(chunk_size).peekable();
+    loop {
+        let chunk = chunks.next().unwrap_or(&[]);
+        let last = chunks.peek().is_none();
+        let mut frame = Vec::with_capacity(chunk.len() + 1);
+        frame.push(if last { LAST_FRAGMENT } else { MORE_FRAGMENTS });
+        frame.extend_from_slice(chunk);
+        frames.push(frame);
+        if last {
+            return frames;
+        }
+    }
+}
+
+impl SerializedChannel for TipcChannel {
+    const MAX_SIZE: usize = 4000;
+    fn execute(&mut self, serialized_req: &[u8]) -> binder::Result<Vec<u8>> {
+        match self.transact(serialized_req) {
+            Ok(rsp) => Ok(rsp),
+            // A transport-level failure on a live channel usually means the TA restarted. Rebuild
+            // the channel and retry the full request exactly once. While already reconnecting (the
+            // nested `send_hal_info` transaction) we do not recurse — the error propagates instead.
+            Err(e) if !self.reconnecting => {
+                error!("TIPC transaction failed ({:?}); reconnecting and retrying once.", e);
+                thread::sleep(RECONNECT_BACKOFF);
+                self.reconnect()?;
+                self.transact(serialized_req)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Build and validate a local DICE/BCC chain when [`LOCAL_DICE_PROPERTY`] is set.
+///
+/// The seed and stage measurement come from the secure-boot configuration; here we read the seed
+/// from the property value (hex) and measure the running HAL image. Returns `None` when local DICE
+/// is disabled or the chain cannot be built, in which case the HAL forwards to the TA.
+fn build_local_dice() -> Option<dice::LocalDiceChain> {
+    let seed_hex = rustutils::system_properties::read(LOCAL_DICE_PROPERTY).ok().flatten()?;
+    if seed_hex.is_empty() {
+        return None;
+    }
+    let seed = match hex::decode(&seed_hex) {
+        Ok(seed) => seed,
+        Err(e) => {
+            error!("Ignoring malformed {} value: {:?}", LOCAL_DICE_PROPERTY, e);
+            return None;
+        }
+    };
+    let measurement = match std::fs::read("/proc/self/exe") {
+        Ok(measurement) => measurement,
+        Err(e) => {
+            // The chain must be bound to a hash of the HAL stage; attesting over an empty
+            // measurement would produce a valid-looking chain over `hash(&[])`. Fall back to TA
+            // forwarding instead.
+            error!("Cannot measure the HAL image for local DICE: {:?}", e);
+            return None;
+        }
+    };
+    match dice::build_local_chain(&seed, &measurement) {
+        Ok(chain) => Some(chain),
+        Err(e) => {
+            error!("Failed to build local DICE chain: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Register one interface under `<service_name>/<instance>` as a lazy (dynamic) service.
+///
+/// Lazy registration lets `servicemanager` start the HAL on demand and lets the process exit once
+/// its clients disconnect. All four interfaces go through here so instance selection and the lazy
+/// wiring stay identical across them.
+fn register_lazy(service_name: &str, instance: &str, binder: binder::SpIBinder) {
+    let name = format!("{}/{}", service_name, instance);
+    binder::register_lazy_service(&name, binder).unwrap_or_else(|e| {
+        panic!("Failed to register service {} because of {:?}.", name, e);
+    });
+    info!("Registered lazy service {}.", name);
 }
 
 fn main() {
@@ -104,37 +262,36 @@ fn main() {
     binder::ProcessState::start_thread_pool();
 
     // Create connection to the TA
-    let connection = trusty::TipcChannel::connect(DEFAULT_DEVICE, TRUSTY_KEYMINT_RUST_SERVICE_NAME)
+    let connection = TipcChannel::connect(DEFAULT_DEVICE, TRUSTY_KEYMINT_RUST_SERVICE_NAME)
         .unwrap_or_else(|e| panic!("Failed to connect to Trusty Keymint TA because of {:?}.", e));
-    let tipc_channel = Arc::new(Mutex::new(TipcChannel(connection)));
+    let tipc_channel = Arc::new(Mutex::new(connection));
+
+    let instance = service_instance();
+    info!("Publishing KeyMint HAL services under the \"{}\" instance.", instance);
 
     // Register the Keymint service
     let km_service = keymint::Device::new_as_binder(tipc_channel.clone());
-    let km_service_name = format!("{}/{}", KM_SERVICE_NAME, SERVICE_INSTANCE);
-    binder::add_service(&km_service_name, km_service.as_binder()).unwrap_or_else(|e| {
-        panic!("Failed to register service {} because of {:?}.", km_service_name, e);
-    });
+    register_lazy(KM_SERVICE_NAME, instance, km_service.as_binder());
 
-    // Register the Remotely Provisioned Component service
-    let rpc_service = rpc::Device::new_as_binder(tipc_channel.clone());
-    let rpc_service_name = format!("{}/{}", RPC_SERVICE_NAME, SERVICE_INSTANCE);
-    binder::add_service(&rpc_service_name, rpc_service.as_binder()).unwrap_or_else(|e| {
-        panic!("Failed to register service {} because of {:?}.", rpc_service_name, e);
-    });
+    // Register the Remotely Provisioned Component service. In local-DICE configurations the HAL
+    // builds and validates its own BCC and hands it to the component so `generateCertificateRequestV2`
+    // can embed it; otherwise the component forwards to the TA as before.
+    let rpc_service = match build_local_dice() {
+        Some(chain) => {
+            info!("Using locally-generated DICE/BCC chain ({} bytes).", chain.bcc.len());
+            local_rpc::LocalDiceRpcDevice::new_as_binder(tipc_channel.clone(), chain)
+        }
+        None => rpc::Device::new_as_binder(tipc_channel.clone()),
+    };
+    register_lazy(RPC_SERVICE_NAME, instance, rpc_service.as_binder());
 
     // Register the Secure Clock service
     let sclock_service = secureclock::Device::new_as_binder(tipc_channel.clone());
-    let sclock_service_name = format!("{}/{}", SECURE_CLOCK_SERVICE_NAME, SERVICE_INSTANCE);
-    binder::add_service(&sclock_service_name, sclock_service.as_binder()).unwrap_or_else(|e| {
-        panic!("Failed to register service {} because of {:?}.", sclock_service_name, e);
-    });
+    register_lazy(SECURE_CLOCK_SERVICE_NAME, instance, sclock_service.as_binder());
 
     // Register the Shared Secret service
     let ssecret_service = sharedsecret::Device::new_as_binder(tipc_channel.clone());
-    let ssecret_service_name = format!("{}/{}", SHARED_SECRET_SERVICE_NAME, SERVICE_INSTANCE);
-    binder::add_service(&ssecret_service_name, ssecret_service.as_binder()).unwrap_or_else(|e| {
-        panic!("Failed to register service {} because of {:?}.", ssecret_service_name, e);
-    });
+    register_lazy(SHARED_SECRET_SERVICE_NAME, instance, ssecret_service.as_binder());
 
     // Send the HAL service information to the TA
     if let Err(e) = send_hal_info(tipc_channel.lock().unwrap().deref_mut()) {
@@ -144,5 +301,46 @@ fn main() {
     info!("Successfully registered KeyMint HAL services.");
     info!("Joining thread pool now.");
     binder::ProcessState::join_thread_pool();
-    info!("KeyMint HAL service is terminating."); // should not reach here
+    // With lazy registration the process is allowed to exit once its clients disconnect, so unlike
+    // the old eager path this is a normal, expected shutdown.
+    info!("KeyMint HAL service is terminating.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_request_sends_one_final_frame() {
+        let frames = fragment_frames(&[], 3);
+        assert_eq!(frames, vec![vec![LAST_FRAGMENT]]);
+    }
+
+    #[test]
+    fn request_fitting_one_chunk_is_a_single_final_frame() {
+        let frames = fragment_frames(&[1, 2, 3], 3);
+        assert_eq!(frames, vec![vec![LAST_FRAGMENT, 1, 2, 3]]);
+    }
+
+    #[test]
+    fn request_spanning_chunks_marks_all_but_the_last() {
+        let frames = fragment_frames(&[1, 2, 3, 4, 5], 2);
+        assert_eq!(
+            frames,
+            vec![
+                vec![MORE_FRAGMENTS, 1, 2],
+                vec![MORE_FRAGMENTS, 3, 4],
+                vec![LAST_FRAGMENT, 5],
+            ]
+        );
+    }
+
+    #[test]
+    fn exact_multiple_of_chunk_size_ends_with_a_final_frame() {
+        let frames = fragment_frames(&[1, 2, 3, 4], 2);
+        assert_eq!(
+            frames,
+            vec![vec![MORE_FRAGMENTS, 1, 2], vec![LAST_FRAGMENT, 3, 4]]
+        );
+    }
 }
@@ -0,0 +1,105 @@
+//
+// Copyright (C) 2022 The Android Open-Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `IRemotelyProvisionedComponent` wrapper that serves `generateCertificateRequestV2` from a
+//! locally-generated DICE/BCC chain.
+//!
+//! Every call other than `generateCertificateRequestV2` is forwarded to the TA-backed
+//! [`rpc::Device`]; the V2 CSR is assembled here (see [`dice::build_csr`]) so the locally-built
+//! chain is embedded into the request rather than discarded. This is only wired up in local-DICE
+//! bring-up configurations.
+
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+
+use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    DeviceInfo::DeviceInfo,
+    IRemotelyProvisionedComponent::{BnRemotelyProvisionedComponent, IRemotelyProvisionedComponent},
+    MacedPublicKey::MacedPublicKey,
+    ProtectedData::ProtectedData,
+    RpcHardwareInfo::RpcHardwareInfo,
+};
+use kmr_hal::{rpc, SerializedChannel};
+
+use crate::dice::{self, LocalDiceChain};
+
+/// Wraps the TA-backed RKP component with a locally-generated chain.
+pub struct LocalDiceRpcDevice {
+    inner: binder::Strong<dyn IRemotelyProvisionedComponent>,
+    chain: LocalDiceChain,
+}
+
+impl LocalDiceRpcDevice {
+    /// Build the wrapped component as a binder, forwarding to a TA-backed [`rpc::Device`] over
+    /// `channel` for everything except the locally-served V2 CSR.
+    pub fn new_as_binder<T: SerializedChannel + 'static>(
+        channel: Arc<Mutex<T>>,
+        chain: LocalDiceChain,
+    ) -> binder::Strong<dyn IRemotelyProvisionedComponent> {
+        let inner = rpc::Device::new_as_binder(channel);
+        BnRemotelyProvisionedComponent::new_binder(
+            LocalDiceRpcDevice { inner, chain },
+            binder::BinderFeatures::default(),
+        )
+    }
+}
+
+impl binder::Interface for LocalDiceRpcDevice {}
+
+impl IRemotelyProvisionedComponent for LocalDiceRpcDevice {
+    fn getHardwareInfo(&self) -> binder::Result<RpcHardwareInfo> {
+        self.inner.getHardwareInfo()
+    }
+
+    fn generateEcdsaP256KeyPair(
+        &self,
+        test_mode: bool,
+        maced_public_key: &mut MacedPublicKey,
+    ) -> binder::Result<Vec<u8>> {
+        self.inner.generateEcdsaP256KeyPair(test_mode, maced_public_key)
+    }
+
+    fn generateCertificateRequest(
+        &self,
+        test_mode: bool,
+        keys_to_sign: &[MacedPublicKey],
+        endpoint_encryption_cert_chain: &[u8],
+        challenge: &[u8],
+        device_info: &mut DeviceInfo,
+        protected_data: &mut ProtectedData,
+    ) -> binder::Result<Vec<u8>> {
+        self.inner.generateCertificateRequest(
+            test_mode,
+            keys_to_sign,
+            endpoint_encryption_cert_chain,
+            challenge,
+            device_info,
+            protected_data,
+        )
+    }
+
+    fn generateCertificateRequestV2(
+        &self,
+        keys_to_sign: &[MacedPublicKey],
+        challenge: &[u8],
+    ) -> binder::Result<Vec<u8>> {
+        dice::build_csr(&self.chain, keys_to_sign, challenge).map_err(|e| {
+            binder::Status::new_exception(
+                binder::ExceptionCode::ILLEGAL_STATE,
+                Some(&CString::new(format!("Failed to build local CSR: {:?}", e)).unwrap()),
+            )
+        })
+    }
+}